@@ -1,20 +1,37 @@
 use axum::{
+    extract::ConnectInfo,
+    extract::DefaultBodyLimit,
+    extract::Path,
+    extract::Query,
+    extract::Request,
     extract::State,
     extract::rejection::JsonRejection,
+    middleware::{self, Next},
+    response::sse::{Event, KeepAlive, Sse},
     response::IntoResponse,
+    response::Response,
     routing::get,
     Json, Router,
 };
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, Method, StatusCode};
+use clap::Parser;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 use tower_http::trace::TraceLayer;
-use tracing::{info, Level};
+use tracing::{info, warn, Level};
 
 #[derive(Serialize, Deserialize, Clone)]
 struct DiskInfo {
@@ -53,11 +70,324 @@ struct HostInfo {
 
     #[serde(default, skip_serializing_if = "Option::is_none")]
     gpu_model: Option<String>,
+
+    /// The connecting address as observed by the server, as opposed to the
+    /// self-reported `ip` above. Ignored on input; always set by the server.
+    #[serde(default, skip_deserializing)]
+    connected_ip: String,
+}
+
+/// Default number of samples retained per host when no override is configured.
+const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// A bounded ring buffer of timestamped samples for a single host, oldest first.
+struct HostHistory {
+    capacity: usize,
+    samples: VecDeque<(SystemTime, HostInfo)>,
+}
+
+impl HostHistory {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, host: HostInfo) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.samples.len() >= self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((SystemTime::now(), host));
+    }
+
+    fn latest(&self) -> Option<&HostInfo> {
+        self.samples.back().map(|(_, host)| host)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistorySample {
+    timestamp: u64,
+    host: HostInfo,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    since: Option<u64>,
+    limit: Option<usize>,
+}
+
+/// One retained sample as stored in an on-disk snapshot, alongside the
+/// connected IP it was keyed under (since `HostInfo::connected_ip` itself
+/// is never trusted from deserialized input).
+#[derive(Serialize, Deserialize)]
+struct SnapshotSample {
+    timestamp: u64,
+    connected_ip: String,
+    host: HostInfo,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Snapshot {
+    hosts: HashMap<String, Vec<SnapshotSample>>,
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 struct AppState {
-    hosts: Arc<Mutex<HashMap<String, HostInfo>>>,
+    hosts: Arc<Mutex<HashMap<String, HostHistory>>>,
+    host_updates: broadcast::Sender<HostInfo>,
+    history_size: usize,
+    write_tokens: Arc<HashSet<String>>,
+    trust_proxy: bool,
+    snapshot_path: Option<Arc<PathBuf>>,
+    dirty: Arc<AtomicBool>,
+    /// Serializes snapshot writes so the periodic flush and the shutdown
+    /// flush never race each other onto the same temp file.
+    snapshot_lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        let (host_updates, _rx) = broadcast::channel(100);
+        Self {
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+            host_updates,
+            history_size: DEFAULT_HISTORY_SIZE,
+            write_tokens: Arc::new(HashSet::new()),
+            trust_proxy: false,
+            snapshot_path: None,
+            dirty: Arc::new(AtomicBool::new(false)),
+            snapshot_lock: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+}
+
+/// Loads a previously written snapshot file into per-host ring buffers sized
+/// to `history_size`. Returns an empty map if the file is missing or invalid.
+fn load_snapshot(path: &PathBuf, history_size: usize) -> HashMap<String, HostHistory> {
+    let Ok(data) = std::fs::read(path) else {
+        return HashMap::new();
+    };
+    let Ok(snapshot) = serde_json::from_slice::<Snapshot>(&data) else {
+        warn!("Ignoring unreadable snapshot at {}", path.display());
+        return HashMap::new();
+    };
+
+    snapshot
+        .hosts
+        .into_iter()
+        .map(|(ip, samples)| {
+            let mut history = HostHistory::new(history_size);
+            for sample in samples {
+                let mut host = sample.host;
+                host.connected_ip = sample.connected_ip;
+                let time = UNIX_EPOCH + Duration::from_secs(sample.timestamp);
+                history.samples.push_back((time, host));
+            }
+            while history.samples.len() > history.capacity {
+                history.samples.pop_front();
+            }
+            (ip, history)
+        })
+        .collect()
+}
+
+/// Builds the serializable snapshot of the current host histories. Cheap
+/// enough to run while holding the `hosts` lock; the actual disk I/O happens
+/// afterwards, off the lock.
+fn build_snapshot(hosts: &HashMap<String, HostHistory>) -> Snapshot {
+    Snapshot {
+        hosts: hosts
+            .iter()
+            .map(|(ip, history)| {
+                let samples = history
+                    .samples
+                    .iter()
+                    .map(|(time, host)| SnapshotSample {
+                        timestamp: time
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs(),
+                        connected_ip: host.connected_ip.clone(),
+                        host: host.clone(),
+                    })
+                    .collect();
+                (ip.clone(), samples)
+            })
+            .collect(),
+    }
+}
+
+/// Writes `snapshot` to `path` atomically: serialize to a temp file in the
+/// same directory, then `rename()` over the target so a crash or a
+/// concurrent write can never leave a truncated/corrupt snapshot on disk.
+/// Blocking, so callers must run this on a blocking thread.
+fn persist_snapshot(path: &PathBuf, snapshot: &Snapshot) {
+    let json = match serde_json::to_vec(snapshot) {
+        Ok(json) => json,
+        Err(err) => {
+            warn!("Failed to serialize snapshot: {err}");
+            return;
+        }
+    };
+
+    let tmp_path = path.with_extension("tmp");
+    if let Err(err) = std::fs::write(&tmp_path, json) {
+        warn!(
+            "Failed to write snapshot temp file {}: {err}",
+            tmp_path.display()
+        );
+        return;
+    }
+    if let Err(err) = std::fs::rename(&tmp_path, path) {
+        warn!("Failed to finalize snapshot at {}: {err}", path.display());
+    }
+}
+
+/// Flushes the current host histories to `path`, serializing concurrent
+/// callers (the periodic flush and the shutdown flush) via `state.snapshot_lock`
+/// and running the blocking file I/O off the async runtime thread.
+async fn write_snapshot(state: &AppState, path: &PathBuf) {
+    let _guard = state.snapshot_lock.lock().await;
+    let snapshot = build_snapshot(&state.hosts.lock().unwrap());
+    let path = path.clone();
+    if let Err(err) = tokio::task::spawn_blocking(move || persist_snapshot(&path, &snapshot)).await
+    {
+        warn!("Snapshot write task panicked: {err}");
+    }
+}
+
+/// Reads `MONITORING_WRITE_TOKENS` as a comma-separated list of accepted bearer
+/// tokens for `POST /hosts`. An empty (or unset) list leaves ingestion open.
+fn load_write_tokens() -> HashSet<String> {
+    std::env::var("MONITORING_WRITE_TOKENS")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|token| !token.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Reads `MONITORING_TRUST_PROXY` to decide whether `X-Forwarded-For` /
+/// `Forwarded` headers are honored when resolving a client's connected IP.
+/// Defaults to `false` so spoofed headers are ignored unless explicitly enabled.
+fn load_trust_proxy() -> bool {
+    std::env::var("MONITORING_TRUST_PROXY")
+        .map(|value| matches!(value.trim(), "1" | "true" | "TRUE" | "True"))
+        .unwrap_or(false)
+}
+
+/// Extracts the `for=` token from a `Forwarded` header value, e.g.
+/// `for=192.0.2.1;proto=https` -> `192.0.2.1`.
+fn parse_forwarded_for(value: &str) -> Option<String> {
+    value.split(';').find_map(|part| {
+        part.trim()
+            .strip_prefix("for=")
+            .map(|ip| ip.trim_matches('"').to_string())
+    })
+}
+
+/// Resolves the address a host report should be attributed to: the real
+/// peer address, or (only when `trust_proxy` is enabled) the left-most
+/// `X-Forwarded-For` entry or the `Forwarded` header's `for=` token.
+fn resolve_connected_ip(trust_proxy: bool, peer: SocketAddr, headers: &HeaderMap) -> String {
+    if trust_proxy {
+        if let Some(forwarded_for) = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty())
+        {
+            return forwarded_for.to_string();
+        }
+
+        if let Some(forwarded) = headers
+            .get(header::FORWARDED)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_forwarded_for)
+        {
+            return forwarded;
+        }
+    }
+
+    peer.ip().to_string()
+}
+
+/// Compares two strings in constant time (with respect to their contents;
+/// the comparison still short-circuits on length). Used so that responding
+/// to `POST /hosts` doesn't leak, via timing, how close a guessed bearer
+/// token is to a real one.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Requires a valid `Authorization: Bearer <token>` header on `POST /hosts`
+/// when at least one write token is configured; other methods pass through.
+async fn require_write_token(
+    State(state): State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if state.write_tokens.is_empty() || req.method() != Method::POST {
+        return Ok(next.run(req).await);
+    }
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let accepted = token.is_some_and(|token| {
+        state
+            .write_tokens
+            .iter()
+            .fold(false, |matched, candidate| {
+                matched | constant_time_eq(candidate, token)
+            })
+    });
+
+    if accepted {
+        Ok(next.run(req).await)
+    } else {
+        Err(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// Command-line configuration for the monitoring server.
+#[derive(Parser)]
+#[command(author, version, about)]
+struct CliArgs {
+    /// Address to bind to.
+    #[arg(long, default_value = "0.0.0.0")]
+    ip: IpAddr,
+
+    /// Port to listen on.
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+
+    /// Maximum accepted size, in bytes, for a single `/hosts` request body.
+    #[arg(long, default_value_t = 10 * 1024 * 1024)]
+    max_body_bytes: usize,
+
+    /// Number of samples retained per host for `/hosts/:ip/history`.
+    #[arg(long, default_value_t = DEFAULT_HISTORY_SIZE)]
+    history_size: usize,
+
+    /// Optional path to persist host state across restarts. When set, the
+    /// snapshot is loaded on startup and flushed periodically and on shutdown.
+    #[arg(long)]
+    snapshot_path: Option<PathBuf>,
 }
 
 #[tokio::main]
@@ -67,31 +397,102 @@ async fn main() {
         .with_max_level(Level::INFO)
         .init();
 
-    let state = AppState::default();
+    let args = CliArgs::parse();
 
-    let app = Router::new()
+    let initial_hosts = args
+        .snapshot_path
+        .as_ref()
+        .map(|path| load_snapshot(path, args.history_size))
+        .unwrap_or_default();
+
+    let state = AppState {
+        hosts: Arc::new(Mutex::new(initial_hosts)),
+        write_tokens: Arc::new(load_write_tokens()),
+        trust_proxy: load_trust_proxy(),
+        history_size: args.history_size,
+        snapshot_path: args.snapshot_path.clone().map(Arc::new),
+        ..AppState::default()
+    };
+
+    if let Some(path) = state.snapshot_path.clone() {
+        let flush_state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                interval.tick().await;
+                if flush_state.dirty.swap(false, Ordering::Relaxed) {
+                    write_snapshot(&flush_state, &path).await;
+                }
+            }
+        });
+    }
+
+    let hosts_route = Router::new()
         .route("/hosts", get(get_hosts).post(update_host))
+        .layer(DefaultBodyLimit::max(args.max_body_bytes))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_write_token,
+        ));
+
+    let shutdown_state = state.clone();
+
+    let app = Router::new()
+        .merge(hosts_route)
+        .route("/hosts/stream", get(stream_hosts))
+        .route("/hosts/:ip/history", get(get_host_history))
+        .route("/metrics", get(get_metrics))
         .with_state(state)
         .layer(TraceLayer::new_for_http()); // logs method, path, status, latency
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
+    let addr = SocketAddr::from((args.ip, args.port));
     let listener = TcpListener::bind(addr).await.unwrap();
     info!("Listening on {addr}");
 
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal(shutdown_state))
+    .await
+    .unwrap();
+}
+
+/// Waits for Ctrl+C, then writes a final snapshot (if configured) before the
+/// server lets in-flight requests finish and exits.
+async fn shutdown_signal(state: AppState) {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("failed to install Ctrl+C handler");
+
+    info!("Shutting down");
+    if let Some(path) = state.snapshot_path.clone() {
+        write_snapshot(&state, &path).await;
+    }
 }
 
 async fn update_host(
     State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
     payload: Result<Json<HostInfo>, JsonRejection>,
 ) -> Result<&'static str, (StatusCode, String)> {
     match payload {
-        Ok(Json(host)) => {
+        Ok(Json(mut host)) => {
+            let connected_ip = resolve_connected_ip(state.trust_proxy, peer, &headers);
+            host.connected_ip = connected_ip.clone();
+
             if let Ok(json_str) = serde_json::to_string_pretty(&host) {
                 info!("Incoming /hosts POST:\n{}", json_str);
             }
             let mut hosts = state.hosts.lock().unwrap();
-            hosts.insert(host.ip.clone(), host);
+            hosts
+                .entry(connected_ip)
+                .or_insert_with(|| HostHistory::new(state.history_size))
+                .push(host.clone());
+            drop(hosts);
+            state.dirty.store(true, Ordering::Relaxed);
+            let _ = state.host_updates.send(host);
             Ok("ok")
         }
         Err(rej) => {
@@ -105,7 +506,162 @@ async fn get_hosts(State(state): State<AppState>) -> impl IntoResponse {
     info!("Incoming /hosts GET request");
 
     let hosts = state.hosts.lock().unwrap();
-    Json(hosts.values().cloned().collect::<Vec<_>>())
+    Json(
+        hosts
+            .values()
+            .filter_map(HostHistory::latest)
+            .cloned()
+            .collect::<Vec<_>>(),
+    )
+}
+
+async fn get_host_history(
+    State(state): State<AppState>,
+    Path(ip): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<HistorySample>>, StatusCode> {
+    info!("Incoming /hosts/{ip}/history GET request");
+
+    let hosts = state.hosts.lock().unwrap();
+    let history = hosts.get(&ip).ok_or(StatusCode::NOT_FOUND)?;
+
+    let since = query.since.unwrap_or(0);
+    let mut samples: Vec<HistorySample> = history
+        .samples
+        .iter()
+        .filter_map(|(time, host)| {
+            let timestamp = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+            (timestamp >= since).then(|| HistorySample {
+                timestamp,
+                host: host.clone(),
+            })
+        })
+        .collect();
+
+    if let Some(limit) = query.limit {
+        if samples.len() > limit {
+            samples = samples.split_off(samples.len() - limit);
+        }
+    }
+
+    Ok(Json(samples))
+}
+
+/// Escapes a string for use as a Prometheus label value: backslashes, double
+/// quotes, and newlines must be escaped per the text exposition format so an
+/// attacker-controlled value (hostname, IP, disk path) can't break out of the
+/// quoted value or inject extra labels.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Formats a sample value for the Prometheus text exposition format. Client
+/// input (e.g. a `cpu_usage` that overflowed to infinity while parsing) can
+/// be non-finite, and Rust's `Display` renders those as `inf`/`NaN`, which
+/// isn't valid exposition syntax; the spec requires exactly `+Inf`/`-Inf`/`NaN`.
+fn format_metric_value(value: f32) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value.is_sign_negative() {
+            "-Inf".to_string()
+        } else {
+            "+Inf".to_string()
+        }
+    } else {
+        value.to_string()
+    }
+}
+
+async fn get_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    info!("Incoming /metrics GET request");
+
+    let hosts = state.hosts.lock().unwrap();
+    let mut body = String::new();
+
+    for host in hosts.values().filter_map(HostHistory::latest) {
+        let labels = format!(
+            "hostname=\"{}\",ip=\"{}\"",
+            escape_label_value(&host.hostname),
+            escape_label_value(&host.ip)
+        );
+
+        body.push_str(&format!(
+            "host_cpu_usage{{{labels}}} {}\n",
+            format_metric_value(host.cpu_usage)
+        ));
+        body.push_str(&format!(
+            "host_memory_usage{{{labels}}} {}\n",
+            format_metric_value(host.memory_usage)
+        ));
+        body.push_str(&format!(
+            "host_cpu_temperature{{{labels}}} {}\n",
+            format_metric_value(host.cpu_temperature)
+        ));
+
+        if let Some(gpu_usage) = host.gpu_usage {
+            body.push_str(&format!(
+                "host_gpu_usage{{{labels}}} {}\n",
+                format_metric_value(gpu_usage)
+            ));
+        }
+        if let Some(gpu_temperature) = host.gpu_temperature {
+            body.push_str(&format!(
+                "host_gpu_temperature{{{labels}}} {}\n",
+                format_metric_value(gpu_temperature)
+            ));
+        }
+
+        for disk in &host.disks {
+            body.push_str(&format!(
+                "host_disk_usage{{{labels},path=\"{}\"}} {}\n",
+                escape_label_value(&disk.path),
+                format_metric_value(disk.usage)
+            ));
+        }
+    }
+
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
+async fn stream_hosts(
+    State(state): State<AppState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    info!("Client subscribed to /hosts/stream");
+
+    let rx = state.host_updates.subscribe();
+    let stream = BroadcastStream::new(rx).filter_map(|msg| match msg {
+        Ok(host) => {
+            // serde_json writes non-finite floats (e.g. a client-supplied
+            // `cpu_usage` that overflowed to infinity) as `null`, so such a
+            // sample is dropped here instead of being sent with bogus values.
+            let mut floats = [host.cpu_usage, host.cpu_frequency, host.cpu_temperature]
+                .into_iter()
+                .chain([host.memory_usage, host.memory_max])
+                .chain([host.gpu_usage, host.gpu_frequency, host.gpu_temperature].into_iter().flatten())
+                .chain(host.disks.iter().flat_map(|disk| [disk.usage, disk.size]));
+            if !host.uptime.is_finite() || floats.any(|value| !value.is_finite()) {
+                warn!("Dropping host update with non-finite values from SSE");
+                return None;
+            }
+            match Event::default().json_data(&host) {
+                Ok(event) => Some(Ok(event)),
+                Err(err) => {
+                    warn!("Dropping host update that failed to serialize for SSE: {err}");
+                    None
+                }
+            }
+        }
+        Err(_) => None,
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(Duration::from_secs(15)))
 }
 
 #[cfg(test)]
@@ -118,40 +674,32 @@ mod tests {
     use http_body_util::BodyExt;
     use tower::util::ServiceExt; // for `oneshot` and `ready`
 
-    fn app() -> Router {
-        let state = AppState::default();
-        Router::new()
+    /// Builds the same route tree as `main`, so tests exercise the real
+    /// auth-gating wiring rather than a stand-in subset of routes.
+    fn build_router(state: AppState) -> Router {
+        let hosts_route = Router::new()
             .route("/hosts", get(get_hosts).post(update_host))
+            .route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                require_write_token,
+            ));
+
+        Router::new()
+            .merge(hosts_route)
+            .route("/hosts/stream", get(stream_hosts))
+            .route("/hosts/:ip/history", get(get_host_history))
+            .route("/metrics", get(get_metrics))
             .with_state(state)
     }
 
-    #[tokio::test]
-    async fn get_hosts_empty() {
-        let app = app();
-
-        let response = app
-            .oneshot(
-                Request::builder()
-                    .uri("/hosts")
-                    .body(Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
-
-        assert_eq!(response.status(), StatusCode::OK);
-        let body = response.into_body().collect().await.unwrap().to_bytes();
-        let hosts: Vec<HostInfo> = serde_json::from_slice(&body).unwrap();
-        assert_eq!(hosts.len(), 0);
+    fn app() -> Router {
+        build_router(AppState::default())
     }
 
-    #[tokio::test]
-    async fn post_host_ok() {
-        let app = app();
-
-        let host_info = HostInfo {
-            hostname: "test-host".to_string(),
-            ip: "127.0.0.1".to_string(),
+    fn sample_host_info(hostname: &str, ip: &str) -> HostInfo {
+        HostInfo {
+            hostname: hostname.to_string(),
+            ip: ip.to_string(),
             uptime: 123.45,
             cpu_usage: 50.0,
             cpu_frequency: 2.5,
@@ -169,21 +717,87 @@ mod tests {
             os_architecture: "x86_64".to_string(),
             cpu_model: "TestCPU".to_string(),
             gpu_model: None,
-        };
-        let host_info_json = serde_json::to_string(&host_info).unwrap();
+            connected_ip: String::new(),
+        }
+    }
+
+    fn post_hosts_request_raw(
+        peer: SocketAddr,
+        body: String,
+        bearer: Option<&str>,
+        forwarded_for: Option<&str>,
+    ) -> Request<Body> {
+        let mut builder = Request::builder()
+            .method(http::Method::POST)
+            .uri("/hosts")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .extension(ConnectInfo(peer));
+
+        if let Some(token) = bearer {
+            builder = builder.header(http::header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        if let Some(forwarded_for) = forwarded_for {
+            builder = builder.header("x-forwarded-for", forwarded_for);
+        }
+
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    fn post_hosts_request(
+        peer: SocketAddr,
+        host_info: &HostInfo,
+        bearer: Option<&str>,
+        forwarded_for: Option<&str>,
+    ) -> Request<Body> {
+        post_hosts_request_raw(
+            peer,
+            serde_json::to_string(host_info).unwrap(),
+            bearer,
+            forwarded_for,
+        )
+    }
+
+    async fn history_samples(app: Router, uri: &str) -> Vec<HistorySample> {
+        let response = app
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn get_hosts_empty() {
+        let app = app();
 
         let response = app
-            .clone()
             .oneshot(
                 Request::builder()
-                    .method(http::Method::POST)
                     .uri("/hosts")
-                    .header(http::header::CONTENT_TYPE, "application/json")
-                    .body(Body::from(host_info_json))
+                    .body(Body::empty())
                     .unwrap(),
             )
             .await
             .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let hosts: Vec<HostInfo> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(hosts.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn post_host_ok() {
+        let app = app();
+        let peer: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let host_info = sample_host_info("test-host", "127.0.0.1");
+
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(peer, &host_info, None, None))
+            .await
+            .unwrap();
         assert_eq!(response.status(), StatusCode::OK);
 
         let response = app
@@ -205,6 +819,7 @@ mod tests {
     #[tokio::test]
     async fn post_host_invalid_json() {
         let app = app();
+        let peer: SocketAddr = "127.0.0.1:9000".parse().unwrap();
 
         let response = app
             .oneshot(
@@ -212,6 +827,7 @@ mod tests {
                     .method(http::Method::POST)
                     .uri("/hosts")
                     .header(http::header::CONTENT_TYPE, "application/json")
+                    .extension(ConnectInfo(peer))
                     .body(Body::from("invalid json"))
                     .unwrap(),
             )
@@ -220,4 +836,325 @@ mod tests {
 
         assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
+
+    #[tokio::test]
+    async fn post_host_requires_configured_token() {
+        let state = AppState {
+            write_tokens: Arc::new(["secret".to_string()].into_iter().collect()),
+            ..AppState::default()
+        };
+        let app = build_router(state);
+        let peer: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+        let host_info = sample_host_info("test-host", "127.0.0.1");
+
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(peer, &host_info, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(peer, &host_info, Some("wrong"), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(post_hosts_request(peer, &host_info, Some("secret"), None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn get_hosts_is_never_gated_by_the_write_token() {
+        let state = AppState {
+            write_tokens: Arc::new(["secret".to_string()].into_iter().collect()),
+            ..AppState::default()
+        };
+        let app = build_router(state);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/hosts")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn hosts_are_keyed_by_peer_ip_not_the_bodys_claimed_ip() {
+        let app = app();
+        let peer: SocketAddr = "10.0.0.5:9000".parse().unwrap();
+        let host_info = sample_host_info("spoofed-host", "1.2.3.4");
+
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(peer, &host_info, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let samples = history_samples(app.clone(), "/hosts/10.0.0.5/history").await;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].host.hostname, "spoofed-host");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/hosts/1.2.3.4/history")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn x_forwarded_for_is_only_honored_when_trust_proxy_is_enabled() {
+        let peer: SocketAddr = "10.0.0.5:9000".parse().unwrap();
+        let host_info = sample_host_info("proxied-host", "1.2.3.4");
+
+        // trust_proxy defaults to false: the forwarded header must be ignored.
+        let app = app();
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(
+                peer,
+                &host_info,
+                None,
+                Some("203.0.113.9"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/hosts/203.0.113.9/history")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // trust_proxy enabled: the forwarded header is used as the key instead.
+        let state = AppState {
+            trust_proxy: true,
+            ..AppState::default()
+        };
+        let app = build_router(state);
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request(
+                peer,
+                &host_info,
+                None,
+                Some("203.0.113.9"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let samples = history_samples(app, "/hosts/203.0.113.9/history").await;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].host.hostname, "proxied-host");
+    }
+
+    #[tokio::test]
+    async fn history_endpoint_respects_capacity_since_and_limit() {
+        let state = AppState {
+            history_size: 2,
+            ..AppState::default()
+        };
+        let app = build_router(state);
+        let peer: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+
+        for i in 0..5 {
+            let host_info = sample_host_info(&format!("host-{i}"), "127.0.0.1");
+            let response = app
+                .clone()
+                .oneshot(post_hosts_request(peer, &host_info, None, None))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // Only the last `history_size` (2) samples should have survived
+        // eviction, in insertion order.
+        let samples = history_samples(app.clone(), "/hosts/127.0.0.1/history").await;
+        assert_eq!(samples.len(), 2);
+        assert_eq!(samples[0].host.hostname, "host-3");
+        assert_eq!(samples[1].host.hostname, "host-4");
+
+        // `since` in the future excludes every retained sample.
+        let samples = history_samples(app.clone(), "/hosts/127.0.0.1/history?since=9999999999").await;
+        assert_eq!(samples.len(), 0);
+
+        // `limit` truncates to the most recent N samples.
+        let samples = history_samples(app, "/hosts/127.0.0.1/history?limit=1").await;
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].host.hostname, "host-4");
+    }
+
+    #[tokio::test]
+    async fn hosts_stream_responds_with_sse_content_type() {
+        let app = app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/hosts/stream")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(http::header::CONTENT_TYPE).unwrap(),
+            "text/event-stream"
+        );
+    }
+
+    #[tokio::test]
+    async fn hosts_stream_skips_non_finite_samples_without_crashing() {
+        let app = app();
+        let peer: SocketAddr = "127.0.0.1:9000".parse().unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/hosts/stream")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut stream_body = response.into_body();
+
+        let overflowing_json = {
+            let json = serde_json::to_string(&sample_host_info("bad-host", "127.0.0.1")).unwrap();
+            // An ordinary, syntactically valid JSON float literal that fits in
+            // an `f64` but exceeds `f32::MAX`, so it becomes `f32::INFINITY`
+            // when narrowed into the `f32` field.
+            json.replacen("\"cpu_usage\":50.0", "\"cpu_usage\":1e39", 1)
+        };
+        let response = app
+            .clone()
+            .oneshot(post_hosts_request_raw(peer, overflowing_json, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let good_host = sample_host_info("good-host", "127.0.0.1");
+        let response = app
+            .oneshot(post_hosts_request(peer, &good_host, None, None))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let frame = tokio::time::timeout(Duration::from_secs(1), stream_body.frame())
+            .await
+            .expect("stream must survive a non-finite sample instead of panicking")
+            .expect("stream should yield a frame")
+            .expect("frame should not error");
+        let data = frame.into_data().expect("frame should carry SSE data");
+        let text = String::from_utf8(data.to_vec()).unwrap();
+
+        assert!(
+            text.contains("good-host"),
+            "expected the well-formed sample's event, got: {text}"
+        );
+        assert!(
+            !text.contains("bad-host"),
+            "the non-finite sample must be dropped rather than surfaced: {text}"
+        );
+    }
+
+    #[test]
+    fn escape_label_value_escapes_backslashes_quotes_and_newlines() {
+        assert_eq!(escape_label_value("plain"), "plain");
+        assert_eq!(escape_label_value("has\"quote"), "has\\\"quote");
+        assert_eq!(escape_label_value("back\\slash"), "back\\\\slash");
+        assert_eq!(escape_label_value("new\nline"), "new\\nline");
+    }
+
+    #[test]
+    fn format_metric_value_uses_prometheus_non_finite_tokens() {
+        assert_eq!(format_metric_value(50.0), "50");
+        assert_eq!(format_metric_value(f32::INFINITY), "+Inf");
+        assert_eq!(format_metric_value(f32::NEG_INFINITY), "-Inf");
+        assert_eq!(format_metric_value(f32::NAN), "NaN");
+    }
+
+    #[test]
+    fn host_history_push_never_exceeds_capacity() {
+        let mut history = HostHistory::new(2);
+        for i in 0..5 {
+            history.push(sample_host_info(&format!("host-{i}"), "127.0.0.1"));
+        }
+        assert_eq!(history.samples.len(), 2);
+        assert_eq!(history.samples[0].1.hostname, "host-3");
+        assert_eq!(history.samples[1].1.hostname, "host-4");
+    }
+
+    #[test]
+    fn host_history_push_with_zero_capacity_retains_nothing() {
+        let mut history = HostHistory::new(0);
+        history.push(sample_host_info("host-0", "127.0.0.1"));
+        assert_eq!(history.samples.len(), 0);
+    }
+
+    #[test]
+    fn snapshot_round_trip_preserves_history_and_connected_ip() {
+        let mut history = HostHistory::new(2);
+        let mut host = sample_host_info("host-a", "127.0.0.1");
+        host.connected_ip = "127.0.0.1".to_string();
+        history.push(host);
+
+        let mut hosts = HashMap::new();
+        hosts.insert("127.0.0.1".to_string(), history);
+
+        let path =
+            std::env::temp_dir().join("monitoring_server_snapshot_round_trip_test.json");
+        let tmp_path = path.with_extension("tmp");
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&tmp_path);
+
+        persist_snapshot(&path, &build_snapshot(&hosts));
+        assert!(path.exists());
+        assert!(!tmp_path.exists(), "temp file must be renamed, not left behind");
+
+        let restored = load_snapshot(&path, 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let restored_history = restored
+            .get("127.0.0.1")
+            .expect("host should survive the round trip");
+        assert_eq!(restored_history.samples.len(), 1);
+        assert_eq!(restored_history.samples[0].1.hostname, "host-a");
+        assert_eq!(restored_history.samples[0].1.connected_ip, "127.0.0.1");
+    }
+
+    #[test]
+    fn load_snapshot_returns_empty_map_when_file_is_missing() {
+        let path = std::env::temp_dir().join("monitoring_server_snapshot_missing_test.json");
+        let _ = std::fs::remove_file(&path);
+
+        let restored = load_snapshot(&path, 10);
+        assert!(restored.is_empty());
+    }
 }